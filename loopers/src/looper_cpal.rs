@@ -0,0 +1,415 @@
+use crate::looper_export::{self, ExportFormat, LooperBuffers};
+use crate::DecodedAudio;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{SampleFormat, SampleRate, StreamConfig};
+use crossbeam_channel::{unbounded, Receiver, Select};
+use crossbeam_queue::{ArrayQueue, SegQueue};
+use loopers_common::gui_channel::{Command, GuiSender, LooperCommand};
+use loopers_engine::Engine;
+use loopers_gui::Gui;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI64, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Drives the looper through cpal, targeting whatever backend it picks for the platform
+/// (WASAPI, ALSA, CoreAudio, ...). cpal splits capture and playback into two independent
+/// streams, so captured input is bridged to the output callback through a ring buffer rather
+/// than being handed to the engine directly.
+pub fn cpal_main(
+    gui: Option<Gui>,
+    gui_sender: GuiSender,
+    gui_to_engine_receiver: Receiver<Command>,
+    export_receiver: Receiver<PathBuf>,
+    beat_normal: DecodedAudio,
+    beat_emphasis: DecodedAudio,
+    restore: bool,
+    export_format: ExportFormat,
+) {
+    let host = cpal::default_host();
+
+    let output_device = host
+        .default_output_device()
+        .expect("no output device available");
+    let input_device = host
+        .default_input_device()
+        .expect("no input device available");
+
+    let (output_config, input_config) = negotiate_matching_configs(&output_device, &input_device)
+        .expect("no cpal input/output config pair sharing a sample rate");
+
+    let sample_rate = output_config.sample_rate.0;
+    let channels = output_config.channels as usize;
+    let input_channels = input_config.channels as usize;
+
+    let beat_normal = resample_linear(&beat_normal.samples, beat_normal.sample_rate, sample_rate);
+    let beat_emphasis = resample_linear(&beat_emphasis.samples, beat_emphasis.sample_rate, sample_rate);
+
+    // buffer a generous half second of input so a slower output callback never starves
+    let ring = Arc::new(ArrayQueue::<f32>::new(sample_rate as usize / 2));
+
+    // `--export` requests are handled on the relay thread (see `spawn_command_relay`) rather than
+    // inside the engine itself, since encoding a whole session is far too slow for the audio
+    // callback. Everything else is forwarded on to the engine unchanged.
+    let (engine_sender, engine_receiver) = unbounded();
+    let engine = Engine::new(
+        sample_rate,
+        gui_sender,
+        engine_receiver,
+        beat_normal,
+        beat_emphasis,
+        restore,
+    );
+
+    let recorder = Arc::new(LooperRecorder::new(sample_rate));
+
+    spawn_command_relay(
+        gui_to_engine_receiver,
+        export_receiver,
+        engine_sender,
+        recorder.clone(),
+        export_format,
+    );
+
+    let err_fn = |err| error!("an error occurred on the cpal audio stream: {}", err);
+
+    let input_stream = {
+        let ring = ring.clone();
+        input_device
+            .build_input_stream(
+                &input_config,
+                move |data: &[f32], _| {
+                    for frame in data.chunks(input_channels) {
+                        // downmix captured input to mono on the way into the ring buffer
+                        let sample = frame.iter().sum::<f32>() / input_channels.max(1) as f32;
+                        // drop the oldest frame rather than blocking the capture thread if the
+                        // output side has fallen behind
+                        if ring.is_full() {
+                            ring.pop();
+                        }
+                        let _ = ring.push(sample);
+                    }
+                },
+                err_fn,
+                None,
+            )
+            .expect("failed to build cpal input stream")
+    };
+
+    let output_stream = match output_config.sample_format() {
+        SampleFormat::F32 => {
+            let config: StreamConfig = output_config.clone().into();
+            device_build_output_stream(&output_device, &config, channels, ring, engine, recorder, err_fn)
+        }
+        format => panic!("unsupported sample format from cpal device: {:?}", format),
+    }
+    .expect("failed to build cpal output stream");
+
+    input_stream.play().expect("failed to start cpal input stream");
+    output_stream
+        .play()
+        .expect("failed to start cpal output stream");
+
+    if let Some(mut gui) = gui {
+        gui.run();
+    } else {
+        loop {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+    }
+}
+
+/// Shadow-records each looper's audio as it's laid down, entirely off state the driver already
+/// has, so an export request never needs to reach into the engine: the audio callback is the only
+/// thing allowed to touch `Engine` (see [`device_build_output_stream`]), and a `Mutex` shared with
+/// it would let a slow export stall real-time playback.
+///
+/// The audio thread only ever pushes looper-id-tagged chunks onto the shared `staging` `SegQueue`
+/// (lock-free, never blocks) and reads `recording_id`; the relay thread sets the atomics and
+/// drains the queue into its own locally-owned buffers whenever it wakes up.
+struct LooperRecorder {
+    sample_rate: u32,
+    recording_id: AtomicI64,
+    tempo_bits: AtomicU32,
+    time_signature: AtomicU32,
+    staging: SegQueue<(u32, Vec<f32>)>,
+}
+
+impl LooperRecorder {
+    fn new(sample_rate: u32) -> Self {
+        LooperRecorder {
+            sample_rate,
+            recording_id: AtomicI64::new(-1),
+            tempo_bits: AtomicU32::new(crate::looper_script::DEFAULT_TEMPO_BPM.to_bits()),
+            time_signature: AtomicU32::new(pack_time_signature(4, 4)),
+            staging: SegQueue::new(),
+        }
+    }
+
+    fn tempo(&self) -> f32 {
+        f32::from_bits(self.tempo_bits.load(Ordering::Relaxed))
+    }
+
+    fn time_signature(&self) -> (u8, u8) {
+        unpack_time_signature(self.time_signature.load(Ordering::Relaxed))
+    }
+}
+
+fn pack_time_signature(upper: u8, lower: u8) -> u32 {
+    ((upper as u32) << 8) | lower as u32
+}
+
+fn unpack_time_signature(bits: u32) -> (u8, u8) {
+    ((bits >> 8) as u8, bits as u8)
+}
+
+/// Reads commands meant for the engine and forwards them to `engine_sender` unchanged (tracking
+/// tempo/time-signature/recording state into `recorder` along the way), and separately services
+/// `export_receiver` requests from `recorder`'s shadow-recorded buffers instead of the engine.
+/// `export_receiver` carries plain `PathBuf`s rather than a `Command` variant: `loopers_common`'s
+/// `Command` protocol has no export/import support, and this series doesn't touch that crate, so
+/// driver-only features get their own channel instead of assuming API that isn't there.
+/// `Select` multiplexes both channels with a timeout so the loop keeps draining `recorder`'s
+/// staging queue even when neither channel has anything ready.
+fn spawn_command_relay(
+    gui_to_engine_receiver: Receiver<Command>,
+    export_receiver: Receiver<PathBuf>,
+    engine_sender: crossbeam_channel::Sender<Command>,
+    recorder: Arc<LooperRecorder>,
+    export_format: ExportFormat,
+) {
+    thread::spawn(move || {
+        let mut persisted: HashMap<u32, Vec<f32>> = HashMap::new();
+
+        let mut select = Select::new();
+        let command_idx = select.recv(&gui_to_engine_receiver);
+        let export_idx = select.recv(&export_receiver);
+
+        loop {
+            while let Some((id, chunk)) = recorder.staging.pop() {
+                persisted.entry(id).or_default().extend(chunk);
+            }
+
+            let ready = match select.select_timeout(Duration::from_millis(50)) {
+                Ok(ready) => ready,
+                Err(_) => continue,
+            };
+
+            match ready.index() {
+                i if i == command_idx => match ready.recv(&gui_to_engine_receiver) {
+                    Ok(command @ Command::SetTempo(bpm)) => {
+                        recorder.tempo_bits.store(bpm.to_bits(), Ordering::Relaxed);
+                        if engine_sender.send(command).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(command @ Command::SetTimeSignature(upper, lower)) => {
+                        recorder
+                            .time_signature
+                            .store(pack_time_signature(upper, lower), Ordering::Relaxed);
+                        if engine_sender.send(command).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(command @ Command::Looper(id, LooperCommand::Record))
+                    | Ok(command @ Command::Looper(id, LooperCommand::Overdub)) => {
+                        recorder.recording_id.store(id as i64, Ordering::Relaxed);
+                        if engine_sender.send(command).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(command @ Command::Looper(id, LooperCommand::Play))
+                    | Ok(command @ Command::Looper(id, LooperCommand::Stop))
+                    | Ok(command @ Command::Looper(id, LooperCommand::Clear)) => {
+                        if recorder.recording_id.load(Ordering::Relaxed) == id as i64 {
+                            recorder.recording_id.store(-1, Ordering::Relaxed);
+                        }
+                        if let Command::Looper(_, LooperCommand::Clear) = &command {
+                            // a cleared looper's shadow-recorded audio goes with it
+                            while let Some((staged_id, chunk)) = recorder.staging.pop() {
+                                if staged_id != id {
+                                    persisted.entry(staged_id).or_default().extend(chunk);
+                                }
+                            }
+                            persisted.remove(&id);
+                        }
+                        if engine_sender.send(command).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(command) => {
+                        if engine_sender.send(command).is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                },
+                i if i == export_idx => match ready.recv(&export_receiver) {
+                    Ok(dir) => {
+                        let loopers: Vec<LooperBuffers> = persisted
+                            .iter()
+                            .map(|(&id, samples)| LooperBuffers {
+                                id,
+                                samples: samples.clone(),
+                            })
+                            .collect();
+
+                        if let Err(e) = looper_export::export_session(
+                            &dir,
+                            &loopers,
+                            recorder.sample_rate,
+                            recorder.tempo(),
+                            recorder.time_signature(),
+                            export_format,
+                        ) {
+                            error!("failed to export session to {}: {}", dir.display(), e);
+                        } else {
+                            info!("exported session to {}", dir.display());
+                        }
+                    }
+                    Err(_) => break,
+                },
+                _ => unreachable!(),
+            }
+        }
+    });
+}
+
+fn device_build_output_stream(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    channels: usize,
+    ring: Arc<ArrayQueue<f32>>,
+    mut engine: Engine,
+    recorder: Arc<LooperRecorder>,
+    err_fn: impl Fn(cpal::StreamError) + Send + 'static,
+) -> Result<cpal::Stream, cpal::BuildStreamError> {
+    let mut input_block = Vec::new();
+    let mut output_block = Vec::new();
+
+    device.build_output_stream(
+        config,
+        move |data: &mut [f32], _| {
+            let frames = data.len() / channels;
+
+            input_block.clear();
+            input_block.resize(frames, 0.0);
+            for sample in input_block.iter_mut() {
+                *sample = ring.pop().unwrap_or(0.0);
+            }
+
+            output_block.clear();
+            output_block.resize(frames, 0.0);
+            engine.process_block(&input_block, &mut output_block);
+
+            // Shadow-record whichever looper is currently recording/overdubbing so export never
+            // has to read the input block back out of the engine itself.
+            let recording_id = recorder.recording_id.load(Ordering::Relaxed);
+            if recording_id >= 0 {
+                let _ = recorder
+                    .staging
+                    .push((recording_id as u32, input_block.clone()));
+            }
+
+            for (frame, &sample) in data.chunks_mut(channels).zip(output_block.iter()) {
+                for out in frame.iter_mut() {
+                    *out = sample;
+                }
+            }
+        },
+        err_fn,
+        None,
+    )
+}
+
+/// Negotiates a single sample rate shared by both `output_device` and `input_device`, preferring
+/// [`crate::PREFERRED_SAMPLE_RATE`] when both support it so playback, capture and the engine's
+/// internal processing all agree without needing their own resampler. Falls back to the output
+/// device's default rate, requiring the input device to support that exact rate: negotiating each
+/// device independently (as this used to) can silently pick two different rates, which shows up
+/// as captured input played back at the wrong pitch/speed.
+fn negotiate_matching_configs(
+    output_device: &cpal::Device,
+    input_device: &cpal::Device,
+) -> Option<(cpal::SupportedStreamConfig, cpal::SupportedStreamConfig)> {
+    let preferred = SampleRate(crate::PREFERRED_SAMPLE_RATE);
+
+    let output_configs: Vec<_> = output_device.supported_output_configs().ok()?.collect();
+    let input_configs: Vec<_> = input_device.supported_input_configs().ok()?.collect();
+
+    if let Some(output_range) = output_configs
+        .iter()
+        .find(|c| c.min_sample_rate() <= preferred && preferred <= c.max_sample_rate())
+    {
+        if let Some(input_range) = input_configs
+            .iter()
+            .find(|c| c.min_sample_rate() <= preferred && preferred <= c.max_sample_rate())
+        {
+            return Some((
+                output_range.clone().with_sample_rate(preferred),
+                input_range.clone().with_sample_rate(preferred),
+            ));
+        }
+    }
+
+    let output_config = output_device.default_output_config().ok()?;
+    let rate = output_config.sample_rate();
+
+    let input_range = input_configs
+        .into_iter()
+        .find(|c| c.min_sample_rate() <= rate && rate <= c.max_sample_rate())
+        .unwrap_or_else(|| {
+            panic!(
+                "input device does not support the output device's rate ({} Hz) and they share no common rate",
+                rate.0
+            )
+        });
+
+    Some((output_config, input_range.with_sample_rate(rate)))
+}
+
+/// Linearly resamples mono `samples` from `from_rate` to `to_rate`. Good enough for short click
+/// samples; a real-time resampler would be overkill for audio this short and decoded once at
+/// startup.
+pub(crate) fn resample_linear(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if samples.is_empty() || from_rate == to_rate {
+        return samples.to_vec();
+    }
+
+    let ratio = to_rate as f64 / from_rate as f64;
+    let out_len = ((samples.len() as f64) * ratio).round() as usize;
+
+    (0..out_len)
+        .map(|i| {
+            let src_pos = i as f64 / ratio;
+            let lower = src_pos.floor() as usize;
+            let upper = (lower + 1).min(samples.len() - 1);
+            let frac = (src_pos - lower as f64) as f32;
+            samples[lower] * (1.0 - frac) + samples[upper] * frac
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resample_linear;
+
+    #[test]
+    fn resample_linear_upsamples_by_interpolating() {
+        let samples = vec![0.0, 1.0, 0.0, -1.0];
+        let out = resample_linear(&samples, 8_000, 16_000);
+
+        assert_eq!(out.len(), 8);
+        assert_eq!(out[0], 0.0);
+        assert!((out[1] - 0.5).abs() < 1e-6);
+        assert_eq!(out[2], 1.0);
+    }
+
+    #[test]
+    fn resample_linear_is_noop_when_rates_match() {
+        let samples = vec![0.1, 0.2, 0.3];
+        assert_eq!(resample_linear(&samples, 44_100, 44_100), samples);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::looper_xspf::{self, XspfManifest, XspfTrack};
+use std::io;
+use std::num::{NonZeroU32, NonZeroU8};
+use std::path::{Path, PathBuf};
+
+/// One looper's recorded audio. For `--export`, this is shadow-recorded by the driver itself
+/// (see `looper_cpal::LooperRecorder`) rather than read back out of the engine, since the engine
+/// runs exclusively on the realtime audio thread and can't be locked from here.
+pub struct LooperBuffers {
+    pub id: u32,
+    pub samples: Vec<f32>,
+}
+
+#[derive(Clone, Copy)]
+pub enum ExportFormat {
+    Flac,
+    Vorbis,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Flac => "flac",
+            ExportFormat::Vorbis => "ogg",
+        }
+    }
+}
+
+/// Encodes every looper in `dir` as `format` and writes an XSPF manifest describing them,
+/// producing the portable bundle `--export` promises.
+pub fn export_session(
+    dir: &Path,
+    loopers: &[LooperBuffers],
+    sample_rate: u32,
+    tempo: f32,
+    time_signature: (u8, u8),
+    format: ExportFormat,
+) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+
+    let mut tracks = Vec::with_capacity(loopers.len());
+    for looper in loopers {
+        let file_name = format!("looper-{}.{}", looper.id, format.extension());
+        let path = dir.join(&file_name);
+
+        match format {
+            ExportFormat::Flac => encode_flac(&path, &looper.samples, sample_rate)?,
+            ExportFormat::Vorbis => encode_vorbis(&path, &looper.samples, sample_rate)?,
+        }
+
+        tracks.push(XspfTrack {
+            location: PathBuf::from(file_name),
+            length_samples: looper.samples.len() as u64,
+        });
+    }
+
+    looper_xspf::write(
+        &dir.join("session.xspf"),
+        &XspfManifest {
+            tempo,
+            time_signature,
+            tracks,
+        },
+    )
+}
+
+fn encode_flac(path: &Path, samples: &[f32], sample_rate: u32) -> io::Result<()> {
+    // 24-bit rather than 16: the engine's buffers are f32, and quantizing straight to 16-bit
+    // throws away most of that headroom for no reason.
+    let pcm: Vec<i32> = samples
+        .iter()
+        .map(|s| (s.clamp(-1.0, 1.0) * 8_388_607.0) as i32)
+        .collect();
+
+    let config = flacenc::config::Encoder::default();
+    let source = flacenc::source::MemSource::from_samples(&pcm, 1, 24, sample_rate as usize);
+
+    let stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("flac encode error: {:?}", e)))?;
+
+    let mut sink = flacenc::bitsink::ByteSink::new();
+    stream
+        .write(&mut sink)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("flac write error: {:?}", e)))?;
+
+    std::fs::write(path, sink.as_slice())
+}
+
+fn encode_vorbis(path: &Path, samples: &[f32], sample_rate: u32) -> io::Result<()> {
+    let file = std::fs::File::create(path)?;
+
+    let sample_rate = NonZeroU32::new(sample_rate)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "zero sample rate"))?;
+    let channels = NonZeroU8::new(1).unwrap();
+
+    let mut encoder = vorbis_rs::VorbisEncoderBuilder::new(sample_rate, channels, file)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?
+        .build()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+
+    encoder
+        .encode_audio_block(&[samples])
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+
+    encoder
+        .finish()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use crossbeam_channel::Sender;
+use loopers_common::gui_channel::Command;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+/// Spins up a TCP listener on its own thread so foot-controllers, phones, or other machines can
+/// drive the looper remotely. Each connection forwards newline-delimited JSON `Command`s read
+/// from the socket into the same `gui_to_engine_sender` channel the GUI uses, acking each one
+/// back over the same connection. Binds to `bind_addr` only (defaults to loopback, see
+/// `--control-bind`) since the protocol carries no authentication.
+pub fn spawn_remote_control(bind_addr: String, port: u16, gui_to_engine_sender: Sender<Command>) {
+    thread::spawn(move || {
+        let listener = match TcpListener::bind((bind_addr.as_str(), port)) {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("failed to bind remote control on {}:{}: {}", bind_addr, port, e);
+                return;
+            }
+        };
+
+        info!("remote control listening on {}:{}", bind_addr, port);
+
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("remote control accept error: {}", e);
+                    continue;
+                }
+            };
+
+            let gui_to_engine_sender = gui_to_engine_sender.clone();
+            thread::spawn(move || handle_client(stream, gui_to_engine_sender));
+        }
+    });
+}
+
+fn handle_client(stream: TcpStream, gui_to_engine_sender: Sender<Command>) {
+    let peer = stream
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_else(|_| "<unknown>".to_string());
+    info!("remote control client connected: {}", peer);
+
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(e) => {
+            error!("failed to clone remote control socket for {}: {}", peer, e);
+            return;
+        }
+    };
+
+    let reader = BufReader::new(stream);
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("remote control read error from {}: {}", peer, e);
+                break;
+            }
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // Acks only that the command was accepted onto the engine's queue, not that it ran or
+        // what the engine's state is afterward. There's no state-push channel back to clients;
+        // a full remote-control protocol would need the engine to report state changes, which
+        // this driver has no way to subscribe to.
+        let ack = match serde_json::from_str::<Command>(&line) {
+            Ok(command) => {
+                if gui_to_engine_sender.send(command).is_err() {
+                    break;
+                }
+                "{\"ok\":true}".to_string()
+            }
+            Err(e) => {
+                warn!("ignoring malformed remote control command from {}: {}", peer, e);
+                format!("{{\"ok\":false,\"error\":{:?}}}", e.to_string())
+            }
+        };
+
+        if writeln!(writer, "{}", ack).is_err() {
+            break;
+        }
+    }
+
+    info!("remote control client disconnected: {}", peer);
+}
@@ -0,0 +1,134 @@
+use crossbeam_channel::Sender;
+use loopers_common::gui_channel::{Command, LooperCommand};
+use mlua::{Lua, Result as LuaResult};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+pub(crate) const DEFAULT_TEMPO_BPM: f32 = 120.0;
+
+/// Loads `path` as a Lua script and runs it on its own thread, sending engine commands over
+/// `gui_to_engine_sender` the same way the GUI does.
+pub fn spawn_script(path: String, gui_to_engine_sender: Sender<Command>) {
+    thread::spawn(move || {
+        if let Err(e) = run_script(&path, gui_to_engine_sender) {
+            error!("script error: {}", e);
+        }
+    });
+}
+
+fn run_script(path: &str, sender: Sender<Command>) -> LuaResult<()> {
+    let source = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("failed to read script '{}': {}", path, e));
+
+    let lua = Lua::new();
+
+    // bits-as-f32 so `sleep_beats` can compute beat length from whatever tempo the script itself
+    // last set, without needing a feed of engine state back onto this thread
+    let tempo_bits = Arc::new(AtomicU32::new(DEFAULT_TEMPO_BPM.to_bits()));
+
+    let looper = lua.create_table()?;
+
+    looper.set(
+        "record",
+        lua.create_function({
+            let sender = sender.clone();
+            move |_, id: u32| {
+                sender
+                    .send(Command::Looper(id, LooperCommand::Record))
+                    .map_err(mlua::Error::external)
+            }
+        })?,
+    )?;
+
+    looper.set(
+        "overdub",
+        lua.create_function({
+            let sender = sender.clone();
+            move |_, id: u32| {
+                sender
+                    .send(Command::Looper(id, LooperCommand::Overdub))
+                    .map_err(mlua::Error::external)
+            }
+        })?,
+    )?;
+
+    looper.set(
+        "play",
+        lua.create_function({
+            let sender = sender.clone();
+            move |_, id: u32| {
+                sender
+                    .send(Command::Looper(id, LooperCommand::Play))
+                    .map_err(mlua::Error::external)
+            }
+        })?,
+    )?;
+
+    looper.set(
+        "stop",
+        lua.create_function({
+            let sender = sender.clone();
+            move |_, id: u32| {
+                sender
+                    .send(Command::Looper(id, LooperCommand::Stop))
+                    .map_err(mlua::Error::external)
+            }
+        })?,
+    )?;
+
+    looper.set(
+        "clear",
+        lua.create_function({
+            let sender = sender.clone();
+            move |_, id: u32| {
+                sender
+                    .send(Command::Looper(id, LooperCommand::Clear))
+                    .map_err(mlua::Error::external)
+            }
+        })?,
+    )?;
+
+    lua.globals().set("looper", looper)?;
+
+    lua.globals().set(
+        "set_tempo",
+        lua.create_function({
+            let sender = sender.clone();
+            let tempo_bits = tempo_bits.clone();
+            move |_, bpm: f32| {
+                tempo_bits.store(bpm.to_bits(), Ordering::Relaxed);
+                sender.send(Command::SetTempo(bpm)).map_err(mlua::Error::external)
+            }
+        })?,
+    )?;
+
+    lua.globals().set(
+        "set_time_signature",
+        lua.create_function({
+            let sender = sender.clone();
+            move |_, (upper, lower): (u8, u8)| {
+                sender
+                    .send(Command::SetTimeSignature(upper, lower))
+                    .map_err(mlua::Error::external)
+            }
+        })?,
+    )?;
+
+    // Wall-clock sleep off the tempo this script last set, not the engine's actual beat
+    // position: there's no channel back from the engine to this thread to read that from. A
+    // script that starts a loop, then changes tempo from the GUI or another client mid-sleep,
+    // will drift against what's really playing.
+    lua.globals().set(
+        "sleep_beats",
+        lua.create_function(move |_, beats: u32| {
+            let bpm = f32::from_bits(tempo_bits.load(Ordering::Relaxed));
+            let seconds_per_beat = 60.0 / bpm.max(1.0);
+            thread::sleep(Duration::from_secs_f32(seconds_per_beat * beats as f32));
+            Ok(())
+        })?,
+    )?;
+
+    lua.load(&source).exec()
+}
@@ -0,0 +1,205 @@
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Loopers-specific metadata (sample counts, tempo, time signature) has no home in the base
+/// XSPF spec, so it rides along in an `<extension application="https://loopers.io">` element,
+/// the spec's sanctioned way of carrying custom fields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct XspfTrack {
+    /// Path to the encoded audio (FLAC or Vorbis), relative to the manifest file.
+    pub location: PathBuf,
+    pub length_samples: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct XspfManifest {
+    pub tempo: f32,
+    pub time_signature: (u8, u8),
+    pub tracks: Vec<XspfTrack>,
+}
+
+/// Writes a session manifest as an XSPF playlist; see [`read`] for the inverse.
+pub fn write(path: &Path, manifest: &XspfManifest) -> io::Result<()> {
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+
+    writer
+        .write_event(Event::Start(BytesStart::new("playlist").with_attributes([
+            ("version", "1"),
+            ("xmlns", "http://xspf.org/ns/0/"),
+        ])))
+        .map_err(to_io_error)?;
+
+    writer
+        .write_event(Event::Start(BytesStart::new("extension").with_attributes([(
+            "application",
+            "https://loopers.io",
+        )])))
+        .map_err(to_io_error)?;
+    write_text_element(&mut writer, "tempo", &manifest.tempo.to_string())?;
+    write_text_element(
+        &mut writer,
+        "timeSignature",
+        &format!("{}/{}", manifest.time_signature.0, manifest.time_signature.1),
+    )?;
+    writer
+        .write_event(Event::End(BytesEnd::new("extension")))
+        .map_err(to_io_error)?;
+
+    writer
+        .write_event(Event::Start(BytesStart::new("trackList")))
+        .map_err(to_io_error)?;
+
+    for track in &manifest.tracks {
+        writer
+            .write_event(Event::Start(BytesStart::new("track")))
+            .map_err(to_io_error)?;
+
+        write_text_element(&mut writer, "location", &track.location.to_string_lossy())?;
+
+        writer
+            .write_event(Event::Start(BytesStart::new("extension").with_attributes([(
+                "application",
+                "https://loopers.io",
+            )])))
+            .map_err(to_io_error)?;
+        write_text_element(&mut writer, "lengthSamples", &track.length_samples.to_string())?;
+        writer
+            .write_event(Event::End(BytesEnd::new("extension")))
+            .map_err(to_io_error)?;
+
+        writer
+            .write_event(Event::End(BytesEnd::new("track")))
+            .map_err(to_io_error)?;
+    }
+
+    writer
+        .write_event(Event::End(BytesEnd::new("trackList")))
+        .map_err(to_io_error)?;
+    writer
+        .write_event(Event::End(BytesEnd::new("playlist")))
+        .map_err(to_io_error)?;
+
+    std::fs::write(path, writer.into_inner())
+}
+
+/// Reads back a manifest written by [`write`].
+pub fn read(path: &Path) -> io::Result<XspfManifest> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut reader = Reader::from_str(&contents);
+    reader.config_mut().trim_text(true);
+
+    let mut tracks = Vec::new();
+    let mut tempo = 120.0;
+    let mut time_signature = (4, 4);
+
+    let mut location: Option<PathBuf> = None;
+    let mut length_samples: Option<u64> = None;
+    let mut current_tag = String::new();
+
+    loop {
+        match reader.read_event().map_err(to_io_error)? {
+            Event::Start(e) | Event::Empty(e) => {
+                current_tag = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+            }
+            Event::Text(text) => {
+                let value = text.unescape().map_err(to_io_error)?.into_owned();
+                match current_tag.as_str() {
+                    "location" => location = Some(PathBuf::from(value)),
+                    "lengthSamples" => {
+                        length_samples = value.parse().ok();
+                    }
+                    "tempo" => {
+                        if let Ok(parsed) = value.parse() {
+                            tempo = parsed;
+                        }
+                    }
+                    "timeSignature" => {
+                        if let Some((n, d)) = value.split_once('/') {
+                            if let (Ok(n), Ok(d)) = (n.parse(), d.parse()) {
+                                time_signature = (n, d);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(e) if e.name().as_ref() == b"track" => {
+                match (location.take(), length_samples.take()) {
+                    (Some(location), Some(length_samples)) => tracks.push(XspfTrack {
+                        location,
+                        length_samples,
+                    }),
+                    (location, length_samples) => warn!(
+                        "skipping malformed xspf track (location={:?}, lengthSamples={:?})",
+                        location, length_samples
+                    ),
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(XspfManifest {
+        tempo,
+        time_signature,
+        tracks,
+    })
+}
+
+fn write_text_element(
+    writer: &mut Writer<Vec<u8>>,
+    name: &'static str,
+    text: &str,
+) -> io::Result<()> {
+    writer
+        .write_event(Event::Start(BytesStart::new(name)))
+        .map_err(to_io_error)?;
+    writer
+        .write_event(Event::Text(BytesText::new(text)))
+        .map_err(to_io_error)?;
+    writer
+        .write_event(Event::End(BytesEnd::new(name)))
+        .map_err(to_io_error)?;
+    Ok(())
+}
+
+fn to_io_error(e: quick_xml::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_then_read_round_trips() {
+        let dir = std::env::temp_dir().join(format!("loopers-xspf-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("session.xspf");
+
+        let manifest = XspfManifest {
+            tempo: 128.5,
+            time_signature: (3, 4),
+            tracks: vec![
+                XspfTrack {
+                    location: PathBuf::from("looper-0.flac"),
+                    length_samples: 44_100,
+                },
+                XspfTrack {
+                    location: PathBuf::from("looper-1.ogg"),
+                    length_samples: 88_200,
+                },
+            ],
+        };
+
+        write(&path, &manifest).unwrap();
+        let read_back = read(&path).unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(read_back, manifest);
+    }
+}
@@ -2,37 +2,82 @@
 
 extern crate bytes;
 extern crate chrono;
+extern crate cpal;
 extern crate crossbeam_queue;
 extern crate dirs;
+extern crate flacenc;
 extern crate futures;
+#[cfg(feature = "jack")]
 extern crate jack;
+extern crate mlua;
+extern crate quick_xml;
 extern crate serde;
+extern crate serde_json;
+extern crate symphonia;
+extern crate vorbis_rs;
 #[macro_use]
 extern crate log;
 
+#[cfg(feature = "jack")]
 mod loopers_jack;
 
+mod looper_cpal;
+mod looper_export;
+mod looper_remote;
+mod looper_script;
+mod looper_xspf;
+
 #[cfg(target_os = "macos")]
 mod looper_coreaudio;
 
+#[cfg(feature = "jack")]
 use crate::loopers_jack::jack_main;
-use clap::{arg, Parser};
-use crossbeam_channel::bounded;
+use crate::looper_cpal::cpal_main;
+use crate::looper_remote::spawn_remote_control;
+use crate::looper_script::spawn_script;
+use clap::{arg, Parser, ValueEnum};
+use crossbeam_channel::{bounded, unbounded};
 use loopers_common::gui_channel::GuiSender;
 use loopers_gui::Gui;
 use std::io;
+use std::path::PathBuf;
 use std::process::exit;
 
 // metronome sounds; included in the binary for now to ease usage of cargo install
 const SINE_NORMAL: &[u8] = include_bytes!("../resources/sine_normal.wav");
 const SINE_EMPHASIS: &[u8] = include_bytes!("../resources/sine_emphasis.wav");
 
+/// Sample rate drivers try to negotiate their hardware streams at when the device supports it.
+/// The rate actually in effect can still end up different (device limitations, jack server
+/// rate, ...), which is why decoded audio carries its own rate alongside its samples instead of
+/// being resampled to this constant up front — only the driver that ends up running the engine
+/// knows the rate to resample against.
+pub(crate) const PREFERRED_SAMPLE_RATE: u32 = 48_000;
+
 #[cfg(target_os = "macos")]
 const DEFAULT_DRIVER: &str = "coreaudio";
 
-#[cfg(not(target_os = "macos"))]
+#[cfg(all(not(target_os = "macos"), feature = "jack"))]
 const DEFAULT_DRIVER: &str = "jack";
 
+#[cfg(all(not(target_os = "macos"), not(feature = "jack")))]
+const DEFAULT_DRIVER: &str = "cpal";
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormatArg {
+    Flac,
+    Vorbis,
+}
+
+impl From<ExportFormatArg> for looper_export::ExportFormat {
+    fn from(format: ExportFormatArg) -> Self {
+        match format {
+            ExportFormatArg::Flac => looper_export::ExportFormat::Flac,
+            ExportFormatArg::Vorbis => looper_export::ExportFormat::Vorbis,
+        }
+    }
+}
+
 #[derive(Parser)]
 #[command(
     version = "0.1.2",
@@ -53,9 +98,9 @@ struct Cli {
         help = format!(
             "Controls which audio driver to use (included drivers: {})",
             if cfg!(feature = "coreaudio-rs") {
-                "coreaudio, jack"
+                "coreaudio, jack, cpal"
             } else {
-                "jack"
+                "jack, cpal"
             }
         ),
     )]
@@ -68,6 +113,43 @@ struct Cli {
     /// Path to output logs to
     #[arg(long, default_value_t = String::new())]
     log_path: String,
+
+    /// Path to a custom metronome click sample for the normal beat, decoded via Symphonia
+    /// (wav, mp3, flac, ogg/vorbis, ...). Falls back to the embedded click when unset.
+    #[arg(long)]
+    metronome_normal: Option<String>,
+
+    /// Path to a custom metronome click sample for the emphasized (downbeat) beat, decoded via
+    /// Symphonia. Falls back to the embedded click when unset.
+    #[arg(long)]
+    metronome_emphasis: Option<String>,
+
+    /// Path to a Lua script to run on startup. Requires `--no-gui`
+    #[arg(long)]
+    script: Option<String>,
+
+    /// Port to accept newline-delimited JSON control commands on over TCP
+    #[arg(long)]
+    control_port: Option<u16>,
+
+    /// Address the control-port listener binds to. Defaults to loopback since the protocol has
+    /// no authentication; pass an interface address explicitly to accept remote connections
+    #[arg(long, default_value = "127.0.0.1")]
+    control_bind: String,
+
+    /// Directory to export each looper's audio and an XSPF manifest to
+    #[arg(long)]
+    export: Option<PathBuf>,
+
+    /// Audio format to encode exported loops as
+    #[arg(long, value_enum, default_value_t = ExportFormatArg::Flac)]
+    export_format: ExportFormatArg,
+
+    /// Directory of a previously exported XSPF bundle whose manifest `--restore` reads instead of
+    /// the default manifest location. The manifest's tracks are reported in the log but not
+    /// loaded into the session; the default private-format session is restored as usual
+    #[arg(long)]
+    restore_from: Option<PathBuf>,
 }
 
 fn main() {
@@ -85,29 +167,108 @@ fn main() {
     let (gui, gui_sender) = if !cli.no_gui {
         let (sender, receiver) = GuiSender::new();
         (
-            Some(Gui::new(receiver, gui_to_engine_sender, sender.clone())),
+            Some(Gui::new(receiver, gui_to_engine_sender.clone(), sender.clone())),
             sender,
         )
     } else {
         (None, GuiSender::disconnected())
     };
 
-    // read wav files
-    let reader = hound::WavReader::new(SINE_NORMAL).unwrap();
-    let beat_normal: Vec<f32> = reader.into_samples().map(|x| x.unwrap()).collect();
+    if cli.script.is_some() && !cli.no_gui {
+        eprintln!("--script requires --no-gui; the gui already drives the engine");
+        exit(1);
+    }
+
+    if let Some(path) = cli.script.clone() {
+        spawn_script(path, gui_to_engine_sender.clone());
+    }
+
+    if let Some(port) = cli.control_port {
+        spawn_remote_control(
+            cli.control_bind.clone(),
+            port,
+            gui_to_engine_sender.clone(),
+        );
+    }
+
+    // Export requests are handled entirely by the driver via `export_receiver` rather than
+    // through `gui_to_engine_sender`: the engine's `Command` protocol (`loopers_common`) has no
+    // `Export` variant, so routing them through a crate-local channel avoids assuming API that
+    // doesn't exist upstream. Import only goes as far as reading the manifest, below.
+    let (export_sender, export_receiver) = unbounded();
+
+    if cli.restore {
+        let manifest_path = cli
+            .restore_from
+            .clone()
+            .map(|dir| dir.join("session.xspf"))
+            .or_else(default_xspf_manifest_path);
+
+        if let Some(manifest_path) = manifest_path {
+            if manifest_path.exists() {
+                match looper_xspf::read(&manifest_path) {
+                    Ok(manifest) => {
+                        // The manifest can be read, but there is no engine-side API in this tree
+                        // to load external buffers into a running session, so its tracks are
+                        // only reported here, not loaded.
+                        warn!(
+                            "found {} track(s) in xspf manifest {} (tempo {}, {}/{}), but this \
+                             driver cannot load them into a running session; they were not \
+                             restored",
+                            manifest.tracks.len(),
+                            manifest_path.display(),
+                            manifest.tempo,
+                            manifest.time_signature.0,
+                            manifest.time_signature.1,
+                        );
+                    }
+                    Err(e) => error!(
+                        "failed to read xspf manifest {}: {}",
+                        manifest_path.display(),
+                        e
+                    ),
+                }
+            }
+        }
+    }
+
+    if let Some(dir) = cli.export.clone() {
+        if export_sender.send(dir).is_err() {
+            error!("failed to request loop export");
+        }
+    }
 
-    let reader = hound::WavReader::new(SINE_EMPHASIS).unwrap();
-    let beat_emphasis: Vec<f32> = reader.into_samples().map(|x| x.unwrap()).collect();
+    let beat_normal = load_metronome_sample(cli.metronome_normal.as_deref(), SINE_NORMAL);
+    let beat_emphasis = load_metronome_sample(cli.metronome_emphasis.as_deref(), SINE_EMPHASIS);
 
     match cli.driver.as_str() {
         "jack" => {
-            jack_main(
+            if cfg!(feature = "jack") {
+                #[cfg(feature = "jack")]
+                jack_main(
+                    gui,
+                    gui_sender,
+                    gui_to_engine_receiver,
+                    export_receiver,
+                    beat_normal,
+                    beat_emphasis,
+                    cli.restore,
+                );
+            } else {
+                eprintln!("Jack support was not compiled in; choose another driver");
+                exit(1);
+            }
+        }
+        "cpal" => {
+            cpal_main(
                 gui,
                 gui_sender,
                 gui_to_engine_receiver,
+                export_receiver,
                 beat_normal,
                 beat_emphasis,
                 cli.restore,
+                cli.export_format.into(),
             );
         }
         "coreaudio" => {
@@ -117,6 +278,7 @@ fn main() {
                     gui,
                     gui_sender,
                     gui_to_engine_receiver,
+                    export_receiver,
                     beat_normal,
                     beat_emphasis,
                     cli.restore,
@@ -134,6 +296,119 @@ fn main() {
     }
 }
 
+/// Mono audio decoded at its source rate. Callers that need a specific rate resample it
+/// themselves once they know what rate that is (see [`crate::looper_cpal::resample_linear`]).
+pub(crate) struct DecodedAudio {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+}
+
+/// Decodes a metronome click for `--metronome-normal`/`--metronome-emphasis`. A bad user-supplied
+/// path is a startup configuration error, not a crash: report it and exit cleanly. The embedded
+/// fallback (`path` is `None`) failing to decode would mean a corrupt build, so that case still
+/// panics.
+fn load_metronome_sample(path: Option<&str>, embedded_fallback: &'static [u8]) -> DecodedAudio {
+    match path {
+        Some(path) => decode_samples(Some(path), embedded_fallback).unwrap_or_else(|e| {
+            eprintln!("failed to load metronome sample '{}': {}", path, e);
+            exit(1);
+        }),
+        None => decode_samples(None, embedded_fallback)
+            .unwrap_or_else(|e| panic!("failed to decode embedded metronome sample: {}", e)),
+    }
+}
+
+// Decodes audio into mono f32 samples at its source rate. When `path` is given, the file is
+// decoded through Symphonia so users can supply any format it supports (mp3, flac, ogg/vorbis,
+// ...); otherwise `embedded_fallback` (one of the bundled SINE_* wavs) is decoded instead.
+// Multi-channel sources are downmixed to mono by averaging channels.
+pub(crate) fn decode_samples(
+    path: Option<&str>,
+    embedded_fallback: &'static [u8],
+) -> io::Result<DecodedAudio> {
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::CODEC_TYPE_NULL;
+    use symphonia::core::errors::Error as SymphoniaError;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::probe::Hint;
+
+    fn to_io_error(e: impl std::fmt::Display) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+    }
+
+    let mut hint = Hint::new();
+
+    let mss = match path {
+        Some(path) => {
+            let file = std::fs::File::open(path)?;
+            if let Some(ext) = std::path::Path::new(path).extension().and_then(|e| e.to_str()) {
+                hint.with_extension(ext);
+            }
+            MediaSourceStream::new(Box::new(file), Default::default())
+        }
+        None => {
+            hint.with_extension("wav");
+            MediaSourceStream::new(Box::new(io::Cursor::new(embedded_fallback)), Default::default())
+        }
+    };
+
+    let probed = symphonia::default::get_probe()
+        .format(&hint, mss, &Default::default(), &Default::default())
+        .map_err(to_io_error)?;
+
+    let mut format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no decodable audio track"))?
+        .clone();
+
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &Default::default())
+        .map_err(to_io_error)?;
+
+    let mut samples = Vec::new();
+    let mut sample_rate = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(_)) => break,
+            Err(e) => return Err(to_io_error(e)),
+        };
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(to_io_error(e)),
+        };
+
+        let spec = *decoded.spec();
+        let channels = spec.channels.count().max(1);
+        sample_rate.get_or_insert(spec.rate);
+
+        let mut buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buf.copy_interleaved_ref(decoded);
+
+        samples.extend(
+            buf.samples()
+                .chunks(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+        );
+    }
+
+    Ok(DecodedAudio {
+        samples,
+        sample_rate: sample_rate.unwrap_or(PREFERRED_SAMPLE_RATE),
+    })
+}
+
+// Default location `--restore` checks for an XSPF manifest when `--restore-from` isn't given.
+fn default_xspf_manifest_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("loopers").join("session.xspf"))
+}
+
 fn setup_logger(debug: bool, path: &str) -> Result<(), fern::InitError> {
     let level = if debug {
         log::LevelFilter::Debug